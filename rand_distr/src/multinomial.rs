@@ -13,8 +13,9 @@ use crate::{Binomial, Distribution};
 use rand::Rng;
 
 
-/// Error type returned from `Multinomial::new`.
+/// Error type returned from `MultinomialConst::new` and `Multinomial::new`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
     /// There is a negative weight or Nan
     ProbabilityNegative,
@@ -22,6 +23,8 @@ pub enum Error {
     ProbabilityZero,
     /// One of the weights is inf or the sum overflows
     ProbabilityInfinity,
+    /// The weights do not sum close enough to 1.0, see [`MultinomialConst::new_checked`]
+    ProbabilitySumInvalid,
 }
 
 impl std::fmt::Display for Error {
@@ -30,10 +33,15 @@ impl std::fmt::Display for Error {
             Error::ProbabilityNegative => "One of the weights is negative or Nan",
             Error::ProbabilityZero => "All of the weights are zero",
             Error::ProbabilityInfinity => "One of the weights is inf or the sum overflows",
+            Error::ProbabilitySumInvalid => "The weights do not sum close enough to 1.0",
         })
     }
 }
 
+/// Maximum allowed deviation of the weight sum from `1.0` accepted by
+/// [`MultinomialConst::new_checked`].
+const SUM_TOLERANCE: f64 = 1e-6;
+
 /// Multinomial Distribution with compile time known number of categories.
 #[derive(Debug, Clone, PartialEq)]
 pub struct MultinomialConst<const K: usize> {
@@ -45,7 +53,12 @@ pub struct MultinomialConst<const K: usize> {
 }
 
 fn normalize<const K: usize>(weights: &mut [f64; K]) -> Result<(), Error> {
-    if weights.iter().any(|&x| x < 0.0) {
+    normalize_slice(weights.as_mut_slice())
+}
+
+fn normalize_slice(weights: &mut [f64]) -> Result<(), Error> {
+    // `x < 0.0` alone would miss NaN, since every comparison against NaN is false.
+    if weights.iter().any(|&x| !(x >= 0.0)) {
         return Err(Error::ProbabilityNegative);
     }
 
@@ -78,42 +91,284 @@ impl<const K: usize> MultinomialConst<K> {
 
         Ok(MultinomialConst { n, weights })
     }
+
+    /// Constructs a new `MultinomialConst`, rejecting weights whose sum is not close to `1.0`.
+    ///
+    /// Unlike [`new`](Self::new), which silently rescales weights summing to anything other
+    /// than `1.0` (dividing every weight by their sum), this validates that the sum is within
+    /// [`SUM_TOLERANCE`] of `1.0` before normalizing, returning
+    /// [`Error::ProbabilitySumInvalid`] otherwise. This catches mistakes such as a typo in one
+    /// weight that would otherwise silently corrupt the whole sample.
+    pub fn new_checked(n: u64, mut weights: [f64; K]) -> Result<Self, Error> {
+        if K == 0 {
+            panic!("MultinomialConst<0> is not a valid type");
+        }
+
+        // `x < 0.0` alone would miss NaN, since every comparison against NaN is false.
+        if weights.iter().any(|&x| !(x >= 0.0)) {
+            return Err(Error::ProbabilityNegative);
+        }
+
+        let sum: f64 = weights.iter().sum();
+
+        if sum == f64::INFINITY {
+            return Err(Error::ProbabilityInfinity);
+        }
+
+        if (sum - 1.0).abs() > SUM_TOLERANCE {
+            return Err(Error::ProbabilitySumInvalid);
+        }
+
+        normalize(&mut weights)?;
+
+        Ok(MultinomialConst { n, weights })
+    }
+
+    /// Returns `true` if `x` is in the support of the distribution, i.e. every component is
+    /// non-negative and the components sum to `n`.
+    ///
+    /// Zero weights are not taken into account: a point with `x_i > 0` for a category with
+    /// weight `0.0` is supported, but has probability `0.0`.
+    pub fn supports(&self, x: &[u64; K]) -> bool {
+        x.iter().sum::<u64>() == self.n
+    }
+
+    /// The log of the probability mass function, `ln P(x)`.
+    ///
+    /// Computed as `lgamma(n+1) - Σ lgamma(x_i+1) + Σ x_i·ln(p_i)` for numerical stability.
+    /// Returns `-inf` if `x` is not in the support of the distribution.
+    pub fn ln_pmf(&self, x: &[u64; K]) -> f64 {
+        if !self.supports(x) {
+            return f64::NEG_INFINITY;
+        }
+
+        let mut result = ln_gamma(self.n as f64 + 1.0);
+        for i in 0..K {
+            result -= ln_gamma(x[i] as f64 + 1.0);
+            // 0 * ln(0) is treated as 0, so terms with x_i == 0 are skipped
+            if x[i] > 0 {
+                result += x[i] as f64 * self.weights[i].ln();
+            }
+        }
+
+        result
+    }
+
+    /// The probability mass function, `P(x)`.
+    ///
+    /// Returns `0.0` if `x` is not in the support of the distribution.
+    pub fn pmf(&self, x: &[u64; K]) -> f64 {
+        self.ln_pmf(x).exp()
+    }
+
+    /// The mean of each category, `n·p_i`.
+    pub fn mean(&self) -> [f64; K] {
+        let mut mean = [0.0; K];
+        for i in 0..K {
+            mean[i] = self.n as f64 * self.weights[i];
+        }
+        mean
+    }
+
+    /// The covariance matrix of the distribution.
+    ///
+    /// The diagonal holds the per-category variance `n·p_i·(1 − p_i)` and the off-diagonal
+    /// entries `(i, j)` hold the covariance `−n·p_i·p_j`.
+    pub fn covariance(&self) -> [[f64; K]; K] {
+        let mut covariance = [[0.0; K]; K];
+        for i in 0..K {
+            for j in 0..K {
+                covariance[i][j] = if i == j {
+                    self.n as f64 * self.weights[i] * (1.0 - self.weights[i])
+                } else {
+                    -(self.n as f64) * self.weights[i] * self.weights[j]
+                };
+            }
+        }
+        covariance
+    }
+}
+
+/// Natural log of the gamma function, via the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation, g = 7, n = 9.
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_571e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // Reflection formula
+        std::f64::consts::PI.ln() - (std::f64::consts::PI * x).sin().ln() - ln_gamma(1.0 - x)
+    } else {
+        let x = x - 1.0;
+        let mut a = COEFFICIENTS[0];
+        let t = x + G + 0.5;
+        for (i, &c) in COEFFICIENTS.iter().enumerate().skip(1) {
+            a += c / (x + i as f64);
+        }
+
+        0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+    }
 }
 
 impl<const K: usize> Distribution<[u64; K]> for MultinomialConst<K> {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> [u64; K] {
-        // This follows the binomial approach in "The computer generation of multinomial random variates" by Charles S. Davis
-        // Se also the numpy soruce for random_multinomial
+        let mut sample = [0u64; K];
+        sample_multinomial(self.n, &self.weights, &mut sample, rng);
+        sample
+    }
+}
+
+/// Multinomial Distribution where the number of categories is only known at runtime.
+///
+/// This is the counterpart of [`MultinomialConst`] for situations where the number of
+/// categories is not known at compile time, for example when categories are read from data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multinomial {
+    /// Number of draws
+    n: u64,
+    /// normalized weights for the multinomial distribution
+    /// Garantied to be not negative and they should add to a value close to 1.0
+    weights: Vec<f64>,
+}
 
-        // We assume K >= 1
-        // We assume that self.weights are all non negative and finite
-        // If the weights sum up < 1.0 the last component will get the remaining weight
-        // If the weights sum up > 1.0 the components after the first i with weights[..i] > 1.0 will get zero weights
+impl Multinomial {
+    /// Constructs a new `Multinomial` which samples `Vec<u64>` samples for a runtime known
+    /// number of categories.
+    ///
+    /// `weights` will be normalized so it sums up to 1.
+    pub fn new<W: Into<Vec<f64>>>(n: u64, weights: W) -> Result<Self, Error> {
+        let mut weights = weights.into();
 
-        let mut sample = [0u64; K];
-        let mut remaining_p = 1.0;
-        let mut remaining_n = self.n;
+        if weights.is_empty() {
+            panic!("Multinomial with 0 categories is not a valid distribution");
+        }
+
+        normalize_slice(&mut weights)?;
 
-        for i in 0..(K - 1) {
-            if remaining_p <= 0.0 {
-                break;
+        Ok(Multinomial { n, weights })
+    }
+}
+
+impl Distribution<Vec<u64>> for Multinomial {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<u64> {
+        let mut sample = vec![0u64; self.weights.len()];
+        sample_multinomial(self.n, &self.weights, &mut sample, rng);
+        sample
+    }
+}
+
+/// This follows the binomial approach in "The computer generation of multinomial random variates" by Charles S. Davis
+/// See also the numpy source for random_multinomial
+///
+/// We assume `weights.len() >= 1` and `out.len() == weights.len()`.
+/// We assume that `weights` are all non negative and finite.
+/// If the weights sum up < 1.0 the last component will get the remaining weight.
+/// If the weights sum up > 1.0 the components after the first i with weights[..i] > 1.0 will get zero weights.
+fn sample_multinomial<R: Rng + ?Sized>(n: u64, weights: &[f64], out: &mut [u64], rng: &mut R) {
+    let k = weights.len();
+    let mut remaining_p = 1.0;
+    let mut remaining_n = n;
+
+    for i in 0..(k - 1) {
+        if remaining_p <= 0.0 {
+            break;
+        }
+
+        // It's possible that weights/remaining_p can become slightly bigger than 1.0
+        let binomial = Binomial::new(remaining_n, (weights[i] / remaining_p).min(1.0))
+            .expect("We know that prob is between 0.0 and 1.0");
+        out[i] = binomial.sample(rng);
+        // This cannot overflow because out[i] is garantied to be <= remaining_n, because it's a binomial sample
+        remaining_n -= out[i];
+        if remaining_n == 0 {
+            break;
+        }
+        remaining_p -= weights[i];
+    }
+
+    out[k - 1] = remaining_n;
+}
+
+/// `serde` support for the multinomial distributions.
+///
+/// `MultinomialConst` and `Multinomial` store already-normalized weights, so a hand-written or
+/// tampered payload (negative, NaN or infinite weights) must re-run `normalize_slice` on
+/// deserialization rather than being trusted as-is.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::{normalize_slice, Multinomial, MultinomialConst};
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // `weights` is stored as a `Vec<f64>` rather than `[f64; K]`: serde only implements
+    // (de)serialization of fixed-size arrays for concrete lengths 0..=32, not for a generic
+    // `const K`, so deriving on an array field here would fail to compile.
+    #[derive(Serialize, Deserialize)]
+    struct MultinomialConstRepr {
+        n: u64,
+        weights: Vec<f64>,
+    }
+
+    impl<const K: usize> Serialize for MultinomialConst<K> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            MultinomialConstRepr {
+                n: self.n,
+                weights: self.weights.to_vec(),
             }
+            .serialize(serializer)
+        }
+    }
 
-            // It's possible that weights/remaining_p can become slightly bigger than 1.0
-            let binomial = Binomial::new(remaining_n, (self.weights[i] / remaining_p).min(1.0))
-                .expect("We know that prob is between 0.0 and 1.0");
-            sample[i] = binomial.sample(rng);
-            // This cannot overflow because sample[i] is garantied to be <= remaining_n, because it's a binomial sample
-            remaining_n -= sample[i];
-            if remaining_n == 0 {
-                break;
+    impl<'de, const K: usize> Deserialize<'de> for MultinomialConst<K> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let MultinomialConstRepr { n, mut weights } =
+                MultinomialConstRepr::deserialize(deserializer)?;
+            if weights.len() != K {
+                return Err(D::Error::custom(format!(
+                    "invalid length {}, expected {} weights",
+                    weights.len(),
+                    K
+                )));
             }
-            remaining_p -= self.weights[i];
+            normalize_slice(&mut weights).map_err(D::Error::custom)?;
+            // The length check above guarantees this conversion succeeds.
+            let weights: [f64; K] = weights.try_into().unwrap();
+            Ok(MultinomialConst { n, weights })
         }
+    }
 
-        sample[K - 1] = remaining_n;
+    #[derive(Serialize, Deserialize)]
+    struct MultinomialRepr {
+        n: u64,
+        weights: Vec<f64>,
+    }
 
-        sample
+    impl Serialize for Multinomial {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            MultinomialRepr {
+                n: self.n,
+                weights: self.weights.clone(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Multinomial {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let MultinomialRepr { n, mut weights } = MultinomialRepr::deserialize(deserializer)?;
+            normalize_slice(&mut weights).map_err(D::Error::custom)?;
+            Ok(Multinomial { n, weights })
+        }
     }
 }
 
@@ -122,4 +377,110 @@ mod test {
 
     #[test]
     fn test_multinomial() {}
+
+    #[test]
+    fn test_multinomial_runtime() {
+        use super::{Distribution, Error, Multinomial};
+
+        let dist = Multinomial::new(100, vec![0.2, 0.3, 0.5]).unwrap();
+        let sample = dist.sample(&mut rand::thread_rng());
+        assert_eq!(sample.len(), 3);
+        assert_eq!(sample.iter().sum::<u64>(), 100);
+
+        assert_eq!(
+            Multinomial::new(10, vec![-1.0, 2.0]),
+            Err(Error::ProbabilityNegative)
+        );
+    }
+
+    #[test]
+    fn test_multinomial_pmf() {
+        use super::MultinomialConst;
+
+        let dist = MultinomialConst::new(2, [0.5, 0.5]).unwrap();
+        assert!(dist.supports(&[1, 1]));
+        assert!((dist.pmf(&[1, 1]) - 0.5).abs() < 1e-12);
+        assert!((dist.pmf(&[2, 0]) - 0.25).abs() < 1e-12);
+
+        // Not in the support: components don't sum to n.
+        assert!(!dist.supports(&[1, 2]));
+        assert_eq!(dist.pmf(&[1, 2]), 0.0);
+        assert_eq!(dist.ln_pmf(&[1, 2]), f64::NEG_INFINITY);
+
+        // x_i == 0 for a zero-probability category contributes 0, not NaN; x_i > 0 for a
+        // zero-probability category drives the pmf to 0.
+        let degenerate = MultinomialConst::new(3, [1.0, 0.0]).unwrap();
+        assert!((degenerate.pmf(&[3, 0]) - 1.0).abs() < 1e-9);
+        assert_eq!(degenerate.pmf(&[2, 1]), 0.0);
+    }
+
+    #[test]
+    fn test_multinomial_moments() {
+        use super::MultinomialConst;
+
+        let dist = MultinomialConst::new(10, [0.2, 0.3, 0.5]).unwrap();
+
+        let mean = dist.mean();
+        let expected_mean = [2.0, 3.0, 5.0];
+        for i in 0..3 {
+            assert!((mean[i] - expected_mean[i]).abs() < 1e-9);
+        }
+
+        let covariance = dist.covariance();
+        let expected_covariance = [
+            [1.6, -0.6, -1.0],
+            [-0.6, 2.1, -1.5],
+            [-1.0, -1.5, 2.5],
+        ];
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((covariance[i][j] - expected_covariance[i][j]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_multinomial_serde() {
+        use super::MultinomialConst;
+
+        let dist = MultinomialConst::new(10, [0.2, 0.3, 0.5]).unwrap();
+        let json = serde_json::to_string(&dist).unwrap();
+        let round_tripped: MultinomialConst<3> = serde_json::from_str(&json).unwrap();
+        assert_eq!(dist, round_tripped);
+
+        // A hand-written payload with a negative weight must be rejected, not silently
+        // accepted as a valid (if nonsensical) distribution.
+        let tampered = r#"{"n":10,"weights":[-0.2,0.3,0.9]}"#;
+        assert!(serde_json::from_str::<MultinomialConst<3>>(tampered).is_err());
+
+        // A payload whose weight count doesn't match `K` must also be rejected.
+        let wrong_len = r#"{"n":10,"weights":[0.2,0.3]}"#;
+        assert!(serde_json::from_str::<MultinomialConst<3>>(wrong_len).is_err());
+    }
+
+    #[test]
+    fn test_multinomial_new_checked() {
+        use super::{Error, MultinomialConst};
+
+        assert!(MultinomialConst::new_checked(10, [0.5, 0.5]).is_ok());
+        assert!(MultinomialConst::new_checked(10, [0.5, 0.5 + 1e-9]).is_ok());
+
+        assert_eq!(
+            MultinomialConst::new_checked(10, [0.5, 0.4]),
+            Err(Error::ProbabilitySumInvalid)
+        );
+        assert_eq!(
+            MultinomialConst::new_checked(10, [0.5, 0.6]),
+            Err(Error::ProbabilitySumInvalid)
+        );
+        assert_eq!(
+            MultinomialConst::new_checked(10, [0.5, -0.5]),
+            Err(Error::ProbabilityNegative)
+        );
+        assert_eq!(
+            MultinomialConst::new_checked(10, [0.5, f64::NAN]),
+            Err(Error::ProbabilityNegative)
+        );
+    }
 }